@@ -1,3 +1,6 @@
+#![feature(allocator_api)]
+#![feature(coerce_unsized)]
+#![feature(unsize)]
 //! [`Lineage`]`<T>` is a type of cell that allows replacing the contained value while the current value may
 //! still be borrowed. This is safe because old values are stored until explicitly cleared.
 //!
@@ -19,8 +22,10 @@
 //! value is stored inline within the `Lineage`. Only calling [`Lineage::set`] causes a heap allocation.
 
 mod lineage;
+mod unique;
 
-pub use crate::lineage::Lineage;
+pub use crate::lineage::{Lineage, LineageUnsized};
+pub use crate::unique::Unique;
 
 #[cfg(test)]
 mod test;
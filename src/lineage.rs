@@ -1,13 +1,27 @@
-use std::{
-    fmt, mem, ptr, ptr::NonNull, sync::atomic::AtomicPtr, sync::atomic::Ordering::Acquire,
-    sync::atomic::Ordering::Relaxed, sync::atomic::Ordering::SeqCst,
+use std::{alloc::handle_alloc_error, alloc::Allocator, alloc::Global, alloc::Layout, fmt, mem, ptr, ptr::NonNull};
+
+#[cfg(not(feature = "single-threaded"))]
+use std::sync::atomic::{
+    AtomicPtr, Ordering::Acquire, Ordering::Relaxed, Ordering::SeqCst,
 };
 
-struct AtomicLinkedList<T> {
+#[cfg(feature = "single-threaded")]
+use std::cell::Cell;
+
+struct AtomicLinkedList<T, A: Allocator = Global> {
+    #[cfg(not(feature = "single-threaded"))]
     head: AtomicPtr<Node<T>>,
+    #[cfg(feature = "single-threaded")]
+    head: Cell<*mut Node<T>>,
+    alloc: A,
 }
 
-unsafe impl<T> Send for AtomicLinkedList<T> where T: Send {}
+unsafe impl<T, A> Send for AtomicLinkedList<T, A>
+where
+    T: Send,
+    A: Allocator + Send,
+{
+}
 
 // we must require "T: Send" because of the existence of "Lineage::set" and "Lineage::into_inner".
 // imagine T is Sync but not Send and we own a value of type T on a thread B. further imagine we
@@ -15,35 +29,37 @@ unsafe impl<T> Send for AtomicLinkedList<T> where T: Send {}
 // the lineage followed by calling "Lineage::into_inner" on thread A to take ownership of the value.
 // we just sent the value from thread B to thread A even though T is not Send. to prevent this
 // lineage must not be Sync.
-unsafe impl<T> Sync for AtomicLinkedList<T> where T: Send + Sync {}
+//
+// under the "single-threaded" feature the head is a "Cell" and the cheap "set" performs an
+// unsynchronized read-modify-write, so "Lineage" must stay "!Sync". we simply omit the impl and
+// let the "Cell" make "AtomicLinkedList" "!Sync".
+#[cfg(not(feature = "single-threaded"))]
+unsafe impl<T, A> Sync for AtomicLinkedList<T, A>
+where
+    T: Send + Sync,
+    A: Allocator + Sync,
+{
+}
 
-impl<T> Drop for AtomicLinkedList<T> {
+impl<T, A: Allocator> Drop for AtomicLinkedList<T, A> {
     fn drop(&mut self) {
-        mem::drop(LinkedList {
-            head: NonNull::new(*self.head.get_mut()),
-        })
+        unsafe { free_list(NonNull::new(*self.head.get_mut()), &self.alloc) }
     }
 }
 
-struct LinkedList<T> {
-    head: Option<NonNull<Node<T>>>,
-}
-
-unsafe impl<T> Send for LinkedList<T> where T: Send {}
+// drops the value of every node in the chain starting at "head" and frees the node itself through
+// "alloc". the nodes must have been allocated by the same allocator.
+unsafe fn free_list<T, A>(mut head: Option<NonNull<Node<T>>>, alloc: &A)
+where
+    A: Allocator,
+{
+    while let Some(ptr) = head {
+        let next = ptr.as_ref().next;
 
-unsafe impl<T> Sync for LinkedList<T> where T: Sync {}
+        ptr::drop_in_place(ptr::addr_of_mut!((*ptr.as_ptr()).value));
+        alloc.deallocate(ptr.cast(), Layout::new::<Node<T>>());
 
-impl<T> Drop for LinkedList<T> {
-    fn drop(&mut self) {
-        unsafe {
-            let mut cur = self.head;
-            while let Some(ptr) = cur {
-                let Node { value, next } = *Box::from_raw(ptr.as_ptr());
-
-                mem::drop(value);
-                cur = next;
-            }
-        }
+        head = next;
     }
 }
 
@@ -55,12 +71,12 @@ struct Node<T> {
 /// A type of cell that allows replacing the contained value without invalidating references to
 /// the current value.
 #[derive()]
-pub struct Lineage<T> {
+pub struct Lineage<T, A: Allocator = Global> {
     inline: T,
-    list: AtomicLinkedList<T>,
+    list: AtomicLinkedList<T, A>,
 }
 
-impl<T> fmt::Debug for Lineage<T>
+impl<T, A: Allocator> fmt::Debug for Lineage<T, A>
 where
     T: fmt::Debug,
 {
@@ -72,21 +88,38 @@ where
 impl<T> Lineage<T> {
     /// Creates a new `Lineage` with the provided value.
     pub fn new(value: T) -> Self {
+        Lineage::new_in(value, Global)
+    }
+}
+
+impl<T, A: Allocator> Lineage<T, A> {
+    /// Creates a new `Lineage` with the provided value, allocating past values through `alloc`.
+    ///
+    /// Every value stored by [`set`][Lineage::set] is placed in a node allocated from `alloc`, so
+    /// a long-lived `Lineage` can be backed by a bump or arena allocator and release all of its
+    /// retained past values cheaply on [`clear`][Lineage::clear] or drop.
+    pub fn new_in(value: T, alloc: A) -> Self {
         Lineage {
             inline: value,
             list: AtomicLinkedList {
+                #[cfg(not(feature = "single-threaded"))]
                 head: AtomicPtr::new(ptr::null_mut()),
+                #[cfg(feature = "single-threaded")]
+                head: Cell::new(ptr::null_mut()),
+                alloc,
             },
         }
     }
 
     /// Gets a reference to the current value.
     pub fn get(&self) -> &T {
+        #[cfg(not(feature = "single-threaded"))]
+        let head = self.list.head.load(Acquire);
+        #[cfg(feature = "single-threaded")]
+        let head = self.list.head.get();
+
         unsafe {
-            self.list
-                .head
-                .load(Acquire)
-                .as_ref()
+            head.as_ref()
                 .map(|node| &node.value)
                 .unwrap_or(&self.inline)
         }
@@ -110,30 +143,83 @@ impl<T> Lineage<T> {
     ///
     /// Replacing the value does not invalidate existing references to the previous value. The previous
     /// value is kept alive until you call [`clear`][Lineage::clear] or drop the `Lineage`. The new value
-    /// is stored in a [`Box`] which causes a heap allocation.
+    /// is stored in a node allocated through the backing allocator, which causes a heap allocation.
     pub fn set(&self, value: T) {
+        if self.try_set(value).is_err() {
+            handle_alloc_error(Layout::new::<Node<T>>());
+        }
+    }
+
+    /// Replaces the value, returning an error instead of aborting when the allocation fails.
+    ///
+    /// Behaves like [`set`][Lineage::set] but performs the node allocation fallibly. If the backing
+    /// allocator cannot provide memory for the new node the value is handed back to the caller in
+    /// [`Err`] and the `Lineage` is left completely unchanged. On success the node is fully
+    /// initialized before it becomes reachable by any concurrent [`get`][Lineage::get].
+    pub fn try_set(&self, value: T) -> Result<(), T> {
         unsafe {
-            let mut next = self.list.head.load(Acquire);
-            let mut node = NonNull::new_unchecked(Box::into_raw(Box::new(Node {
-                value,
-                next: NonNull::new(next),
-            })));
-
-            while let Err(err) =
-                self.list
-                    .head
-                    .compare_exchange_weak(next, node.as_ptr(), SeqCst, Relaxed)
-            {
-                if next != err {
-                    debug_assert!(!err.is_null());
+            let node = match self.list.alloc.allocate(Layout::new::<Node<T>>()) {
+                Ok(ptr) => ptr.cast::<Node<T>>(),
+                Err(_) => return Err(value),
+            };
 
-                    next = err;
-                    node.as_mut().next = Some(NonNull::new_unchecked(next));
+            #[cfg(not(feature = "single-threaded"))]
+            {
+                let mut node = node;
+                let mut next = self.list.head.load(Acquire);
+                ptr::write(
+                    node.as_ptr(),
+                    Node {
+                        value,
+                        next: NonNull::new(next),
+                    },
+                );
+
+                while let Err(err) =
+                    self.list
+                        .head
+                        .compare_exchange_weak(next, node.as_ptr(), SeqCst, Relaxed)
+                {
+                    if next != err {
+                        debug_assert!(!err.is_null());
+
+                        next = err;
+                        node.as_mut().next = Some(NonNull::new_unchecked(next));
+                    }
                 }
             }
+
+            #[cfg(feature = "single-threaded")]
+            {
+                let next = self.list.head.get();
+                ptr::write(
+                    node.as_ptr(),
+                    Node {
+                        value,
+                        next: NonNull::new(next),
+                    },
+                );
+
+                self.list.head.set(node.as_ptr());
+            }
+
+            Ok(())
         }
     }
 
+    /// Replaces the value with a clone of the current value, returning an error instead of aborting
+    /// when the allocation fails.
+    ///
+    /// This is the fallible counterpart of pushing a fresh copy of the current value. Like
+    /// [`try_set`][Lineage::try_set] the allocation is performed fallibly and the clone is handed
+    /// back in [`Err`] if it fails.
+    pub fn try_clone(&self) -> Result<(), T>
+    where
+        T: Clone,
+    {
+        self.try_set(self.get().clone())
+    }
+
     /// Replaces the value.
     ///
     /// Performs much better than [`set`][Lineage::set] but requires `&mut self`. Does not cause a heap
@@ -151,9 +237,7 @@ impl<T> Lineage<T> {
         if !ptr.is_null() {
             *self.list.head.get_mut() = ptr::null_mut();
 
-            mem::drop(LinkedList {
-                head: NonNull::new(ptr),
-            });
+            unsafe { free_list(NonNull::new(ptr), &self.list.alloc) }
         }
 
         self.inline = value;
@@ -176,21 +260,23 @@ impl<T> Lineage<T> {
     ///
     /// The values are iterated over from newest to oldest. The iterator can safely be dropped, all
     /// remaining values in the iterator will be dropped.
-    pub fn drain(&mut self) -> impl Iterator<Item = T> {
-        struct Drain<T> {
-            list: LinkedList<T>,
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        struct Drain<'a, T, A: Allocator> {
+            head: Option<NonNull<Node<T>>>,
+            alloc: &'a A,
             last: Option<T>,
         }
 
-        impl<T> Iterator for Drain<T> {
+        impl<T, A: Allocator> Iterator for Drain<'_, T, A> {
             type Item = T;
 
             fn next(&mut self) -> Option<Self::Item> {
                 unsafe {
-                    let ptr = self.list.head;
-                    if let Some(ptr) = ptr {
-                        let Node { value, next } = *Box::from_raw(ptr.as_ptr());
-                        self.list.head = next;
+                    if let Some(ptr) = self.head {
+                        let next = ptr.as_ref().next;
+                        let value = ptr::read(ptr::addr_of!((*ptr.as_ptr()).value));
+                        self.alloc.deallocate(ptr.cast(), Layout::new::<Node<T>>());
+                        self.head = next;
 
                         Some(value)
                     } else {
@@ -200,7 +286,7 @@ impl<T> Lineage<T> {
             }
 
             fn size_hint(&self) -> (usize, Option<usize>) {
-                if self.list.head.is_some() {
+                if self.head.is_some() {
                     debug_assert!(self.last.is_some());
 
                     (2, None)
@@ -211,26 +297,32 @@ impl<T> Lineage<T> {
                 }
             }
 
-            fn last(self) -> Option<Self::Item>
+            fn last(mut self) -> Option<Self::Item>
             where
                 Self: Sized,
             {
                 debug_assert!({
                     if self.last.is_none() {
-                        self.list.head.is_none()
+                        self.head.is_none()
                     } else {
                         true
                     }
                 });
 
-                self.last
+                self.last.take()
+            }
+        }
+
+        impl<T, A: Allocator> Drop for Drain<'_, T, A> {
+            fn drop(&mut self) {
+                unsafe { free_list(self.head, self.alloc) }
             }
         }
 
+        let head = NonNull::new(mem::replace(self.list.head.get_mut(), ptr::null_mut()));
         let mut ret = Drain {
-            list: LinkedList {
-                head: NonNull::new(mem::replace(self.list.head.get_mut(), ptr::null_mut())),
-            },
+            head,
+            alloc: &self.list.alloc,
             last: None,
         };
         if let Some(newest) = ret.next() {
@@ -252,12 +344,13 @@ impl<T> Lineage<T> {
     }
 }
 
-impl<T> Clone for Lineage<T>
+impl<T, A> Clone for Lineage<T, A>
 where
     T: Clone,
+    A: Allocator + Clone,
 {
     fn clone(&self) -> Self {
-        Lineage::new(self.get().clone())
+        Lineage::new_in(self.get().clone(), self.list.alloc.clone())
     }
 }
 
@@ -275,3 +368,253 @@ impl<T> From<T> for Lineage<T> {
         Lineage::new(value)
     }
 }
+
+// the unsized variant stores no inline value. every version, including the current one, lives in a
+// node that owns a "Box<T>" so the fat pointer (vtable or slice length) is preserved. the node
+// itself is "Sized" because it only holds pointers, which keeps the list machinery unchanged.
+struct UnsizedNode<T: ?Sized> {
+    value: NonNull<T>,
+    next: Option<NonNull<UnsizedNode<T>>>,
+}
+
+struct AtomicUnsizedList<T: ?Sized, A: Allocator = Global> {
+    #[cfg(not(feature = "single-threaded"))]
+    head: AtomicPtr<UnsizedNode<T>>,
+    #[cfg(feature = "single-threaded")]
+    head: Cell<*mut UnsizedNode<T>>,
+    alloc: A,
+}
+
+unsafe impl<T, A> Send for AtomicUnsizedList<T, A>
+where
+    T: ?Sized + Send,
+    A: Allocator + Send,
+{
+}
+
+#[cfg(not(feature = "single-threaded"))]
+unsafe impl<T, A> Sync for AtomicUnsizedList<T, A>
+where
+    T: ?Sized + Send + Sync,
+    A: Allocator + Sync,
+{
+}
+
+impl<T: ?Sized, A: Allocator> Drop for AtomicUnsizedList<T, A> {
+    fn drop(&mut self) {
+        unsafe { free_unsized_list(NonNull::new(*self.head.get_mut()), &self.alloc) }
+    }
+}
+
+// drops the boxed value of every node in the chain and frees the node itself through "alloc". the
+// values were allocated by the global allocator (they are handed in as "Box<T>"), so they are
+// reconstructed with "Box::from_raw" to run their destructor, while the nodes are freed through the
+// list's allocator.
+unsafe fn free_unsized_list<T, A>(mut head: Option<NonNull<UnsizedNode<T>>>, alloc: &A)
+where
+    T: ?Sized,
+    A: Allocator,
+{
+    while let Some(ptr) = head {
+        let next = ptr.as_ref().next;
+        let value = ptr.as_ref().value;
+
+        let _ = Box::from_raw(value.as_ptr());
+        alloc.deallocate(ptr.cast(), Layout::new::<UnsizedNode<T>>());
+
+        head = next;
+    }
+}
+
+/// A [`Lineage`] for unsized values such as `dyn Trait`, `[T]` or `str`.
+///
+/// Unlike [`Lineage`] there is no inline value: every version is stored as a boxed node, so the
+/// current value is reached through two pointer indirections (list head to node, node to box). This
+/// is the price of supporting fat
+/// pointers, whose metadata must be retained alongside the data pointer.
+pub struct LineageUnsized<T: ?Sized, A: Allocator = Global> {
+    list: AtomicUnsizedList<T, A>,
+}
+
+impl<T, A: Allocator> fmt::Debug for LineageUnsized<T, A>
+where
+    T: ?Sized + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LineageUnsized").field(&self.get()).finish()
+    }
+}
+
+impl<T: ?Sized> LineageUnsized<T> {
+    /// Creates a new `LineageUnsized` with the provided boxed value.
+    pub fn new_boxed(value: Box<T>) -> Self {
+        LineageUnsized::new_boxed_in(value, Global)
+    }
+}
+
+impl<T: ?Sized, A: Allocator> LineageUnsized<T, A> {
+    /// Creates a new `LineageUnsized` with the provided boxed value, allocating nodes through
+    /// `alloc`.
+    pub fn new_boxed_in(value: Box<T>, alloc: A) -> Self {
+        let lineage = LineageUnsized {
+            list: AtomicUnsizedList {
+                #[cfg(not(feature = "single-threaded"))]
+                head: AtomicPtr::new(ptr::null_mut()),
+                #[cfg(feature = "single-threaded")]
+                head: Cell::new(ptr::null_mut()),
+                alloc,
+            },
+        };
+        lineage.set_boxed(value);
+        lineage
+    }
+
+    /// Gets a reference to the current value.
+    pub fn get(&self) -> &T {
+        #[cfg(not(feature = "single-threaded"))]
+        let head = self.list.head.load(Acquire);
+        #[cfg(feature = "single-threaded")]
+        let head = self.list.head.get();
+
+        // the list always holds at least the current value, so "head" is never null.
+        unsafe { (*head).value.as_ref() }
+    }
+
+    /// Gets a mutable reference to the current value.
+    pub fn get_mut(&mut self) -> &mut T {
+        let head = *self.list.head.get_mut();
+
+        unsafe { (*head).value.as_mut() }
+    }
+
+    /// Replaces the value with the provided boxed value.
+    ///
+    /// Replacing the value does not invalidate existing references to the previous value. The
+    /// previous value is kept alive until you call [`clear`][LineageUnsized::clear] or drop the
+    /// `LineageUnsized`. The box is retained as-is, so its fat-pointer metadata survives.
+    pub fn set_boxed(&self, value: Box<T>) {
+        let value = Box::into_raw(value);
+        let layout = Layout::new::<UnsizedNode<T>>();
+
+        unsafe {
+            let node = match self.list.alloc.allocate(layout) {
+                Ok(ptr) => ptr.cast::<UnsizedNode<T>>(),
+                Err(_) => {
+                    // hand the allocation back to the global allocator before aborting so nothing
+                    // is leaked on the failure path.
+                    let _ = Box::from_raw(value);
+                    handle_alloc_error(layout);
+                }
+            };
+
+            #[cfg(not(feature = "single-threaded"))]
+            {
+                let mut next = self.list.head.load(Acquire);
+                ptr::write(
+                    node.as_ptr(),
+                    UnsizedNode {
+                        value: NonNull::new_unchecked(value),
+                        next: NonNull::new(next),
+                    },
+                );
+
+                while let Err(err) =
+                    self.list
+                        .head
+                        .compare_exchange_weak(next, node.as_ptr(), SeqCst, Relaxed)
+                {
+                    if next != err {
+                        debug_assert!(!err.is_null());
+
+                        next = err;
+                        (*node.as_ptr()).next = Some(NonNull::new_unchecked(next));
+                    }
+                }
+            }
+
+            #[cfg(feature = "single-threaded")]
+            {
+                let next = self.list.head.get();
+                ptr::write(
+                    node.as_ptr(),
+                    UnsizedNode {
+                        value: NonNull::new_unchecked(value),
+                        next: NonNull::new(next),
+                    },
+                );
+
+                self.list.head.set(node.as_ptr());
+            }
+        }
+    }
+
+    /// Drops all past values. Does not affect the current value.
+    pub fn clear(&mut self) {
+        let head = *self.list.head.get_mut();
+
+        unsafe {
+            let rest = (*head).next.take();
+            free_unsized_list(rest, &self.list.alloc);
+        }
+    }
+
+    /// Same as [`clear`][LineageUnsized::clear] but returns ownership of the past values.
+    ///
+    /// The values are iterated over from newest to oldest. The iterator can safely be dropped, all
+    /// remaining values in the iterator will be dropped.
+    pub fn drain(&mut self) -> impl Iterator<Item = Box<T>> + '_ {
+        struct Drain<'a, T: ?Sized, A: Allocator> {
+            head: Option<NonNull<UnsizedNode<T>>>,
+            alloc: &'a A,
+        }
+
+        impl<T: ?Sized, A: Allocator> Iterator for Drain<'_, T, A> {
+            type Item = Box<T>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                unsafe {
+                    let ptr = self.head?;
+                    let next = ptr.as_ref().next;
+                    let value = ptr.as_ref().value;
+                    let boxed = Box::from_raw(value.as_ptr());
+                    self.alloc
+                        .deallocate(ptr.cast(), Layout::new::<UnsizedNode<T>>());
+                    self.head = next;
+
+                    Some(boxed)
+                }
+            }
+        }
+
+        impl<T: ?Sized, A: Allocator> Drop for Drain<'_, T, A> {
+            fn drop(&mut self) {
+                unsafe { free_unsized_list(self.head, self.alloc) }
+            }
+        }
+
+        let head = *self.list.head.get_mut();
+        let rest = unsafe { (*head).next.take() };
+
+        Drain {
+            head: rest,
+            alloc: &self.list.alloc,
+        }
+    }
+
+    /// Returns ownership of the current value.
+    pub fn into_inner(mut self) -> Box<T> {
+        self.clear();
+
+        unsafe {
+            let head = *self.list.head.get_mut();
+            let value = (*head).value;
+            let boxed = Box::from_raw(value.as_ptr());
+            self.list
+                .alloc
+                .deallocate(NonNull::new_unchecked(head).cast(), Layout::new::<UnsizedNode<T>>());
+            *self.list.head.get_mut() = ptr::null_mut();
+
+            boxed
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::Lineage;
+use crate::{Lineage, LineageUnsized};
+use std::alloc::{AllocError, Allocator, Global, Layout};
+use std::ptr::NonNull;
+
+#[cfg(not(feature = "single-threaded"))]
 use std::{sync::Arc, thread, time::Duration};
 
 #[test]
@@ -73,6 +77,9 @@ fn t005() {
     }
 }
 
+// sharing a "Lineage" across threads requires it to be "Sync", which the "single-threaded" feature
+// deliberately gives up in exchange for a cheaper "set".
+#[cfg(not(feature = "single-threaded"))]
 #[test]
 fn t006() {
     let l: Arc<Lineage<String>> = Arc::new(Lineage::new("t006".into()));
@@ -132,3 +139,97 @@ fn t007() {
         }
     }
 }
+
+#[test]
+fn t008() {
+    let l: Lineage<String> = Lineage::new("1".into());
+
+    let v1 = l.get();
+    assert!(l.try_set("2".into()) == Ok(()));
+    let v2 = l.get();
+    assert!(l.try_clone() == Ok(()));
+    let v3 = l.get();
+
+    assert!(v1 == "1");
+    assert!(v2 == "2");
+    assert!(v3 == "2");
+}
+
+#[test]
+fn t009() {
+    // dyn Fn: an older borrow keeps working after the function is swapped out.
+    let l: LineageUnsized<dyn Fn() -> i32> = LineageUnsized::new_boxed(Box::new(|| 1));
+    let f1 = l.get();
+    l.set_boxed(Box::new(|| 2));
+    let f2 = l.get();
+    assert!(f1() == 1);
+    assert!(f2() == 2);
+
+    // slice: set_boxed + get + drain.
+    let mut l: LineageUnsized<[u8]> = LineageUnsized::new_boxed(vec![1, 2, 3].into_boxed_slice());
+    assert!(*l.get() == [1, 2, 3]);
+    l.set_boxed(vec![4, 5].into_boxed_slice());
+    assert!(*l.get() == [4, 5]);
+
+    let past: Vec<Box<[u8]>> = l.drain().collect();
+    assert!(past.len() == 1);
+    assert!(*past[0] == [1, 2, 3]);
+    assert!(*l.get() == [4, 5]);
+
+    // str: into_inner returns the current value.
+    let l: LineageUnsized<str> = LineageUnsized::new_boxed(String::from("a").into_boxed_str());
+    l.set_boxed(String::from("b").into_boxed_str());
+    assert!(&*l.into_inner() == "b");
+}
+
+// exercises the unsynchronized "set" exposed under the "single-threaded" feature. The assertions
+// are identical in both modes, so running the suite with "--features single-threaded" covers the
+// "Cell"-based code path.
+#[test]
+fn t010() {
+    let l: Lineage<u32> = Lineage::new(1);
+    let a = l.get();
+    l.set(2);
+    let b = l.get();
+    l.set(3);
+    let c = l.get();
+
+    assert!(*a == 1);
+    assert!(*b == 2);
+    assert!(*c == 3);
+
+    let mut l = l;
+    l.clear();
+    assert!(*l.get() == 3);
+    assert!(l.into_inner() == 3);
+}
+
+// a custom allocator that is deliberately not "Clone", backing all of its work onto "Global". It
+// exists to prove that "clear"/"drain"/"into_inner" work for a non-"Clone" allocator.
+struct NoCloneAlloc;
+
+unsafe impl Allocator for NoCloneAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        Global.deallocate(ptr, layout)
+    }
+}
+
+#[test]
+fn t011() {
+    let mut l: Lineage<String, NoCloneAlloc> = Lineage::new_in("1".into(), NoCloneAlloc);
+    l.set("2".into());
+
+    let past: Vec<String> = l.drain().collect();
+    assert!(past == ["1".to_string()]);
+    assert!(l.get() == "2");
+
+    l.set("3".into());
+    l.clear();
+    assert!(l.get() == "3");
+
+    assert!(l.into_inner() == "3");
+}
@@ -1,58 +1,109 @@
-use std::{fmt, marker::PhantomData, mem, ptr::NonNull};
+use std::{
+    alloc::Allocator, alloc::Global, fmt, marker::PhantomData, marker::Unsize, mem,
+    mem::ManuallyDrop, ops::CoerceUnsized, ptr::NonNull,
+};
 
-pub struct Unique<T> {
+pub struct Unique<T: ?Sized, A: Allocator = Global> {
     ptr: NonNull<T>,
+    alloc: ManuallyDrop<A>,
     _t: PhantomData<T>,
 }
 
-impl<T> Drop for Unique<T> {
+impl<T: ?Sized, A: Allocator> Drop for Unique<T, A> {
     fn drop(&mut self) {
         unsafe {
-            let _ = Box::from_raw(self.ptr.as_ptr());
+            let alloc = ManuallyDrop::take(&mut self.alloc);
+            let _ = Box::from_raw_in(self.ptr.as_ptr(), alloc);
         }
     }
 }
 
-impl<T> fmt::Debug for Unique<T>
+// allows unsizing coercions such as "Unique<[u8; 4]>" to "Unique<[u8]>" or a concrete type to a
+// "Unique<dyn Trait>", exactly mirroring the coercion "Box" provides.
+impl<T, U, A> CoerceUnsized<Unique<U, A>> for Unique<T, A>
 where
-    T: fmt::Debug,
+    T: ?Sized + Unsize<U>,
+    U: ?Sized,
+    A: Allocator,
+{
+}
+
+impl<T, A> fmt::Debug for Unique<T, A>
+where
+    T: ?Sized + fmt::Debug,
+    A: Allocator,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self.get_ref(), f)
+        fmt::Debug::fmt(self.as_ref(), f)
     }
 }
 
-unsafe impl<T> Send for Unique<T> where T: Send {}
+unsafe impl<T, A> Send for Unique<T, A>
+where
+    T: ?Sized + Send,
+    A: Allocator + Send,
+{
+}
 
-unsafe impl<T> Sync for Unique<T> where T: Sync {}
+unsafe impl<T, A> Sync for Unique<T, A>
+where
+    T: ?Sized + Sync,
+    A: Allocator + Sync,
+{
+}
 
 impl<T> Unique<T> {
     pub fn new(value: T) -> Self {
+        Unique::new_in(value, Global)
+    }
+}
+
+impl<T, A: Allocator> Unique<T, A> {
+    pub fn new_in(value: T, alloc: A) -> Self {
+        let (ptr, alloc) = Box::into_raw_with_allocator(Box::new_in(value, alloc));
         unsafe {
             Unique {
-                ptr: NonNull::new_unchecked(Box::into_raw(Box::new(value))),
+                ptr: NonNull::new_unchecked(ptr),
+                alloc: ManuallyDrop::new(alloc),
                 _t: PhantomData,
             }
         }
     }
 
+    pub fn into_inner(mut self) -> T {
+        unsafe {
+            let alloc = ManuallyDrop::take(&mut self.alloc);
+            let ret = *Box::from_raw_in(self.ptr.as_ptr(), alloc);
+            mem::forget(self);
+            ret
+        }
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Unique<T, A> {
     pub fn get_ref(&self) -> &T {
-        unsafe { self.ptr.as_ref() }
+        self.as_ref()
     }
 
     pub fn get_mut(&mut self) -> &mut T {
-        unsafe { self.ptr.as_mut() }
+        self.as_mut()
     }
 
     pub fn get_ptr(&self) -> *mut T {
+        self.as_ptr()
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
         self.ptr.as_ptr()
     }
 
-    pub fn into_inner(self) -> T {
-        unsafe {
-            let ret = *Box::from_raw(self.ptr.as_ptr());
-            mem::forget(self);
-            ret
-        }
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_ref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
     }
 }